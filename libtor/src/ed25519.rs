@@ -0,0 +1,234 @@
+//! A minimal pure-Rust Ed25519 key generator
+//!
+//! [`crate::onion::OnionService`] only ever needs one operation out of the full Ed25519 toolkit:
+//! turning a random 32-byte seed into the `(scalar, prefix)` pair and public key Tor's
+//! `hs_ed25519_secret_key`/`hs_ed25519_public_key` files expect. This implements exactly that,
+//! following the field/point arithmetic of the public-domain TweetNaCl reference
+//! implementation, rather than pulling in a full signing/verification dependency.
+
+use crate::crypto::sha512;
+
+type Fe = [i64; 16];
+
+const FE_ZERO: Fe = [0; 16];
+const FE_ONE: Fe = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+const D2: Fe = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e,
+    0xfce7, 0x56df, 0xd9dc, 0x2406,
+];
+const BASE_X: Fe = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4,
+    0x53fe, 0xcd6e, 0x36d3, 0x2169,
+];
+const BASE_Y: Fe = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666,
+];
+
+fn fe_add(o: &mut Fe, a: &Fe, b: &Fe) {
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+}
+
+fn fe_sub(o: &mut Fe, a: &Fe, b: &Fe) {
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+}
+
+fn fe_mul(o: &mut Fe, a: &Fe, b: &Fe) {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    o.copy_from_slice(&t[..16]);
+    fe_carry(o);
+    fe_carry(o);
+}
+
+fn fe_square(o: &mut Fe, a: &Fe) {
+    let b = *a;
+    fe_mul(o, a, &b);
+}
+
+fn fe_carry(o: &mut Fe) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        let next = if i < 15 { i + 1 } else { 0 };
+        o[next] += c - 1 + 37 * (c - 1) * (if i == 15 { 1 } else { 0 });
+        o[i] -= c << 16;
+    }
+}
+
+fn fe_invert(o: &mut Fe, i: &Fe) {
+    let mut c = *i;
+    for a in (0..254).rev() {
+        let prev = c;
+        fe_square(&mut c, &prev);
+        if a != 2 && a != 4 {
+            let prev = c;
+            fe_mul(&mut c, &prev, i);
+        }
+    }
+    *o = c;
+}
+
+fn fe_select(p: &mut Fe, q: &mut Fe, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn fe_pack(o: &mut [u8; 32], n: &Fe) {
+    let mut t = *n;
+    fe_carry(&mut t);
+    fe_carry(&mut t);
+    fe_carry(&mut t);
+
+    for _ in 0..2 {
+        let mut m = FE_ZERO;
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let carry = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        fe_select(&mut t, &mut m, 1 - carry);
+    }
+
+    for i in 0..16 {
+        o[2 * i] = (t[i] & 0xff) as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+}
+
+/// Extended (X, Y, Z, T) coordinates for a point on the Edwards curve
+type Point = [Fe; 4];
+
+fn point_add(p: &mut Point, q: &Point) {
+    let mut a = FE_ZERO;
+    let mut b = FE_ZERO;
+    let mut c = FE_ZERO;
+    let mut d = FE_ZERO;
+    let mut e = FE_ZERO;
+    let mut f = FE_ZERO;
+    let mut g = FE_ZERO;
+    let mut h = FE_ZERO;
+    let mut t = FE_ZERO;
+
+    fe_sub(&mut a, &p[1], &p[0]);
+    fe_sub(&mut t, &q[1], &q[0]);
+    let a_copy = a;
+    fe_mul(&mut a, &a_copy, &t);
+
+    fe_add(&mut b, &p[0], &p[1]);
+    fe_add(&mut t, &q[0], &q[1]);
+    let b_copy = b;
+    fe_mul(&mut b, &b_copy, &t);
+
+    fe_mul(&mut c, &p[3], &q[3]);
+    let c_copy = c;
+    fe_mul(&mut c, &c_copy, &D2);
+
+    fe_mul(&mut d, &p[2], &q[2]);
+    let d_copy = d;
+    fe_add(&mut d, &d_copy, &d_copy);
+
+    fe_sub(&mut e, &b, &a);
+    fe_sub(&mut f, &d, &c);
+    fe_add(&mut g, &d, &c);
+    fe_add(&mut h, &b, &a);
+
+    fe_mul(&mut p[0], &e, &f);
+    fe_mul(&mut p[1], &h, &g);
+    fe_mul(&mut p[2], &g, &f);
+    fe_mul(&mut p[3], &e, &h);
+}
+
+fn cswap(p: &mut Point, q: &mut Point, b: i64) {
+    for i in 0..4 {
+        fe_select(&mut p[i], &mut q[i], b);
+    }
+}
+
+fn scalar_mult(p: &mut Point, q: &Point, s: &[u8; 32]) {
+    p[0] = FE_ZERO;
+    p[1] = FE_ONE;
+    p[2] = FE_ONE;
+    p[3] = FE_ZERO;
+
+    let mut q = *q;
+    for i in (0..256).rev() {
+        let b = ((s[i / 8] >> (i & 7)) & 1) as i64;
+        cswap(p, &mut q, b);
+        point_add(&mut q, &*p);
+        let p_copy = *p;
+        point_add(p, &p_copy);
+        cswap(p, &mut q, b);
+    }
+}
+
+fn scalar_mult_base(p: &mut Point, s: &[u8; 32]) {
+    let mut base = [FE_ZERO; 4];
+    base[0] = BASE_X;
+    base[1] = BASE_Y;
+    base[2] = FE_ONE;
+    fe_mul(&mut base[3], &BASE_X, &BASE_Y);
+
+    scalar_mult(p, &base, s);
+}
+
+fn pack_point(r: &mut [u8; 32], p: &Point) {
+    let mut zi = FE_ZERO;
+    fe_invert(&mut zi, &p[2]);
+
+    let mut tx = FE_ZERO;
+    let mut ty = FE_ZERO;
+    fe_mul(&mut tx, &p[0], &zi);
+    fe_mul(&mut ty, &p[1], &zi);
+
+    fe_pack(r, &ty);
+
+    let mut tx_bytes = [0u8; 32];
+    fe_pack(&mut tx_bytes, &tx);
+    r[31] ^= (tx_bytes[0] & 1) << 7;
+}
+
+/// Expand a 32-byte Ed25519 seed into `(public_key, scalar || prefix)`
+///
+/// `scalar || prefix` is the 64-byte "expanded" secret key format used both by libsodium's
+/// `crypto_sign_seed_keypair` and by Tor's `hs_ed25519_secret_key` file (after its 32-byte
+/// header).
+pub(crate) fn expand_seed(seed: &[u8; 32]) -> ([u8; 32], [u8; 64]) {
+    let hash = sha512(seed);
+
+    let mut expanded = [0u8; 64];
+    expanded.copy_from_slice(&hash);
+    expanded[0] &= 248;
+    expanded[31] &= 127;
+    expanded[31] |= 64;
+
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&expanded[..32]);
+
+    let mut p: Point = [FE_ZERO; 4];
+    scalar_mult_base(&mut p, &scalar);
+
+    let mut public_key = [0u8; 32];
+    pack_point(&mut public_key, &p);
+
+    (public_key, expanded)
+}
@@ -23,6 +23,7 @@ extern crate log as log_crate;
 extern crate tor_sys;
 
 use std::ffi::CString;
+use std::sync::mpsc::{self, Receiver};
 use std::thread::{self, JoinHandle};
 
 #[allow(unused_imports)]
@@ -30,16 +31,27 @@ use log_crate::{debug, error, info, trace};
 
 #[macro_use]
 pub mod utils;
+/// Control-port client: authenticate against and send commands to a running Tor instance
+pub mod controller;
+mod crypto;
+mod ed25519;
 /// Hidden services related flags
 pub mod hs;
 /// Log related flags
 pub mod log;
+/// Pre-computed v3 onion-service identities
+pub mod onion;
 /// ControlPort and SocksPort related flags
 pub mod ports;
+/// Pluggable-transport related flags
+pub mod pt;
 
+pub use crate::controller::*;
 pub use crate::hs::*;
 pub use crate::log::*;
+pub use crate::onion::*;
 pub use crate::ports::*;
+pub use crate::pt::*;
 use crate::utils::*;
 
 trait Expand: std::fmt::Debug {
@@ -247,8 +259,21 @@ pub enum TorFlag {
     User(String),
     NoExec(TorBool),
 
+    /// `Bridge <transport> <addr:port> <fingerprint> [<transport-specific args>]`
+    ///
+    /// The first field names the pluggable transport to dial through (registered separately with
+    /// [`TorFlag::ClientTransportPlugin`]), or is empty for a plain bridge. The third field holds
+    /// the bridge's fingerprint plus any transport-specific parameters, e.g. for obfs4:
+    /// `Bridge("obfs4", "1.2.3.4:443", "<fingerprint> cert=... iat-mode=0")`.
     Bridge(String, String, String),
 
+    #[expand_to("ClientTransportPlugin {}")]
+    #[expand_to(test = (PluggableTransport::exec("/usr/bin/obfs4proxy").transport("obfs4").transport("meek_lite")) => "ClientTransportPlugin \"obfs4,meek_lite exec /usr/bin/obfs4proxy\"")]
+    ClientTransportPlugin(PluggableTransport),
+    #[expand_to("ServerTransportPlugin {}")]
+    #[expand_to(test = (PluggableTransport::exec("/usr/bin/obfs4proxy").transport("obfs4")) => "ServerTransportPlugin \"obfs4 exec /usr/bin/obfs4proxy\"")]
+    ServerTransportPlugin(PluggableTransport),
+
     ConnectionPadding(TorBool), // TODO: 'auto' not supported at the moment
     ReducedConnectionPadding(TorBool),
     CircuitPadding(TorBool),
@@ -301,12 +326,25 @@ pub enum TorFlag {
 #[derive(Debug, Clone)]
 pub enum Error {
     NotRunning,
+    /// Couldn't connect to or communicate with the control port
+    ControlConnection(String),
+    /// The control port rejected our `AUTHENTICATE` command
+    Authentication(String),
+    /// `tor_run_main` exited with a non-zero status
+    ExitCode(u8),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::NotRunning => write!(f, "Tor service is not running"),
+            Error::ControlConnection(message) => {
+                write!(f, "control port connection failed: {}", message)
+            }
+            Error::Authentication(message) => {
+                write!(f, "control port authentication failed: {}", message)
+            }
+            Error::ExitCode(code) => write!(f, "tor exited with status {}", code),
         }
     }
 }
@@ -328,15 +366,52 @@ impl Tor {
         Default::default()
     }
 
+    /// Build a `Tor` that loads its configuration entirely from a torrc file at `path`
+    ///
+    /// This is equivalent to running `tor -f <path>` with no other flags. Typically used
+    /// together with [`Tor::to_torrc`]/[`Tor::write_torrc`] to generate a file once and reload it
+    /// across runs, instead of re-specifying every flag as a command-line argument.
+    pub fn from_torrc<P: AsRef<std::path::Path>>(path: P) -> Tor {
+        let mut tor = Tor::new();
+        tor.flag(TorFlag::ConfigFile(path.as_ref().to_string_lossy().into_owned()));
+        tor
+    }
+
     /// Add a configuration flag
     pub fn flag(&mut self, flag: TorFlag) -> &mut Tor {
         self.flags.push(flag);
         self
     }
 
+    /// Render every configured flag as a torrc file: one `Key Value` line per flag, reusing each
+    /// flag's [`Expand::expand`] output but joined without the shell quoting `expand_cli` applies
+    ///
+    /// Don't mix [`TorFlag::ConfigFile`] into a flag set rendered this way: it expands to a
+    /// `-f <path>` command-line argument, which isn't a valid torrc directive, so the generated
+    /// file would contain a line Tor can't parse. `ConfigFile` is only meant for a `Tor` built via
+    /// [`Tor::from_torrc`], which is never itself re-rendered through `to_torrc`.
+    pub fn to_torrc(&self) -> String {
+        let mut torrc = self
+            .flags
+            .iter()
+            .map(|flag| flag.expand().join(" "))
+            .collect::<Vec<_>>()
+            .join("\n");
+        torrc.push('\n');
+        torrc
+    }
+
+    /// Write [`Tor::to_torrc`]'s output to `path`
+    pub fn write_torrc<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_torrc())
+    }
+
     /// Start the Tor daemon in the current thread
-    pub fn start(&self) -> Result<u8, Error> {
-        unsafe {
+    ///
+    /// Returns [`Error::ExitCode`] if `tor_run_main` exits with a non-zero status, rather than
+    /// silently reporting success.
+    pub fn start(&self) -> Result<(), Error> {
+        let result = unsafe {
             let config = tor_sys::tor_main_configuration_new();
             let mut argv = vec![String::from("tor")];
             argv.extend_from_slice(
@@ -362,15 +437,197 @@ impl Tor {
 
             tor_sys::tor_main_configuration_free(config);
 
-            Ok(result as u8)
+            result as u8
+        };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(Error::ExitCode(result))
         }
     }
 
     /// Starts the Tor daemon in a background detached thread and return its handle
-    pub fn start_background(&self) -> JoinHandle<Result<u8, Error>> {
+    pub fn start_background(&self) -> JoinHandle<Result<(), Error>> {
         let cloned = self.clone();
         thread::spawn(move || cloned.start())
     }
+
+    /// Starts the Tor daemon in a background thread, returning a [`TorHandle`] that can
+    /// gracefully [`TorHandle::stop`] it via the control port instead of only being reachable by
+    /// killing the whole process
+    pub fn start_owned(&self) -> TorHandle {
+        TorHandle {
+            endpoint: self.control_endpoint(),
+            auth: self.control_auth(),
+            join: self.start_background(),
+        }
+    }
+
+    /// Starts the Tor daemon in a background thread, additionally returning a [`Receiver`] that
+    /// fires once Tor has finished bootstrapping and the SOCKS/control ports are actually usable
+    ///
+    /// The received `Result` distinguishes actually becoming ready from giving up: `Ok(())` means
+    /// Tor reported a finished bootstrap, while `Err` means the control port never came up,
+    /// authentication failed, or Tor exited before finishing, so callers can tell "really ready"
+    /// from "gave up" instead of both looking like the same fired signal.
+    ///
+    /// This only works if a control port was configured (`ControlPort`, `ControlPortAddress` or
+    /// `ControlSocket`); if none was found the receiver fires `Ok(())` immediately, since there is
+    /// no way to observe bootstrap progress. The control port is polled with `GETINFO
+    /// status/bootstrap-phase` until Tor reports `TAG=done`.
+    pub fn start_with_ready_signal(&self) -> (JoinHandle<Result<(), Error>>, Receiver<Result<(), Error>>) {
+        let (tx, rx) = mpsc::channel();
+
+        let endpoint = self.control_endpoint();
+        let auth = self.control_auth();
+        thread::spawn(move || {
+            let outcome = match endpoint {
+                Some(endpoint) => wait_for_bootstrap(&endpoint, &auth),
+                None => Ok(()),
+            };
+            let _ = tx.send(outcome);
+        });
+
+        (self.start_background(), rx)
+    }
+
+    /// The control port this instance is configured to open, if any
+    fn control_endpoint(&self) -> Option<ControlEndpoint> {
+        for flag in &self.flags {
+            match flag {
+                TorFlag::ControlPort(port) => return Some(ControlEndpoint::Tcp(*port)),
+                TorFlag::ControlPortAddress(TorAddress::Port(port), _)
+                | TorFlag::ControlPortAddress(TorAddress::AddressPort(_, port), _) => {
+                    return Some(ControlEndpoint::Tcp(*port))
+                }
+                #[cfg(target_family = "unix")]
+                TorFlag::ControlPortAddress(TorAddress::Unix(path), _) => {
+                    return Some(ControlEndpoint::Unix(path.into()))
+                }
+                #[cfg(target_family = "unix")]
+                TorFlag::ControlSocket(path) => return Some(ControlEndpoint::Unix(path.into())),
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// The authentication method implied by this instance's cookie/password flags
+    ///
+    /// Note that `HashedControlPassword` stores a hash, not the cleartext password, so it cannot
+    /// be recovered here; instances using it should prefer cookie authentication instead, or poll
+    /// readiness manually with their own [`TorController`].
+    fn control_auth(&self) -> ControlAuth {
+        let uses_cookie = self.flags.iter().any(|flag| {
+            matches!(
+                flag,
+                TorFlag::CookieAuthentication(TorBool::True | TorBool::Enabled)
+            )
+        });
+
+        if !uses_cookie {
+            return ControlAuth::Null;
+        }
+
+        let cookie_file = self.flags.iter().find_map(|flag| match flag {
+            TorFlag::CookieAuthFile(path) => Some(std::path::PathBuf::from(path)),
+            _ => None,
+        });
+        let data_directory = self.flags.iter().find_map(|flag| match flag {
+            TorFlag::DataDirectory(dir) => Some(std::path::PathBuf::from(dir)),
+            _ => None,
+        });
+
+        match cookie_file.or_else(|| data_directory.map(|dir| dir.join("control_auth_cookie"))) {
+            Some(path) => ControlAuth::SafeCookie(path),
+            None => ControlAuth::Null,
+        }
+    }
+}
+
+/// A Tor instance running in a background thread, with the means to ask it to shut down instead
+/// of only being reachable by killing the whole process
+///
+/// Returned by [`Tor::start_owned`].
+pub struct TorHandle {
+    join: JoinHandle<Result<(), Error>>,
+    endpoint: Option<ControlEndpoint>,
+    auth: ControlAuth,
+}
+
+impl TorHandle {
+    /// Block until the background thread exits, returning whatever [`Tor::start`] returned
+    pub fn join(self) -> Result<(), Error> {
+        self.join.join().unwrap_or(Ok(()))
+    }
+
+    /// Take ownership of the Tor process via its control port (`TAKEOWNERSHIP`, so Tor exits if
+    /// the connection drops without an explicit signal), request a clean shutdown (`SIGNAL
+    /// SHUTDOWN`) and wait for the background thread to exit
+    ///
+    /// Requires a control port to have been configured (`ControlPort`, `ControlPortAddress` or
+    /// `ControlSocket`); without one there is no way to signal Tor short of dropping the whole
+    /// process, so this returns [`Error::NotRunning`].
+    pub fn stop(self) -> Result<(), Error> {
+        let endpoint = self.endpoint.clone().ok_or(Error::NotRunning)?;
+
+        let mut controller = connect_with_retry(&endpoint)?;
+        controller
+            .authenticate(&self.auth)
+            .map_err(|e| Error::Authentication(e.to_string()))?;
+        controller
+            .take_ownership()
+            .map_err(|e| Error::ControlConnection(e.to_string()))?;
+        controller
+            .signal(Signal::Shutdown)
+            .map_err(|e| Error::ControlConnection(e.to_string()))?;
+
+        self.join()
+    }
+}
+
+/// Connect to `endpoint`, retrying for up to 60s
+///
+/// The control port may not be listening yet right after its owning Tor instance's background
+/// thread is spawned, so both [`wait_for_bootstrap`] and [`TorHandle::stop`] need the same
+/// retry loop rather than failing on the first attempt.
+fn connect_with_retry(endpoint: &ControlEndpoint) -> Result<TorController, Error> {
+    for _ in 0..600 {
+        if let Ok(controller) = TorController::connect(endpoint) {
+            return Ok(controller);
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    Err(Error::ControlConnection(
+        "control port did not come up within 60s".into(),
+    ))
+}
+
+/// Poll `endpoint` until Tor reports it has finished bootstrapping
+///
+/// Connection attempts are retried since the control port may not be listening yet right after
+/// the background thread is spawned. Returns `Err` the moment something gives up on bootstrap
+/// ever finishing (the port never coming up within 60s, authentication failing, or Tor exiting),
+/// so `start_with_ready_signal`'s caller can tell that apart from a real `Ok(())`.
+fn wait_for_bootstrap(endpoint: &ControlEndpoint, auth: &ControlAuth) -> Result<(), Error> {
+    let mut controller = connect_with_retry(endpoint)?;
+
+    controller
+        .authenticate(auth)
+        .map_err(|e| Error::Authentication(e.to_string()))?;
+
+    loop {
+        match controller.get_info("status/bootstrap-phase") {
+            Ok(status) if status.contains("TAG=done") || status.contains("PROGRESS=100") => {
+                return Ok(())
+            }
+            Ok(_) => thread::sleep(std::time::Duration::from_millis(250)),
+            Err(e) => return Err(Error::ControlConnection(e.to_string())),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -393,4 +650,35 @@ mod tests {
 
         std::thread::sleep(std::time::Duration::from_secs(10));
     }
+
+    #[test]
+    fn test_to_torrc_renders_key_value_lines() {
+        let mut tor = Tor::new();
+        tor.flag(TorFlag::SocksPort(9050));
+        tor.flag(TorFlag::DataDirectory("/tmp/tor-rust".into()));
+
+        let torrc = tor.to_torrc();
+        assert!(torrc.lines().any(|line| line == "SocksPort 9050"));
+        assert!(torrc.lines().any(|line| line == "DataDirectory /tmp/tor-rust"));
+        assert!(torrc.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_write_torrc_and_from_torrc_round_trip() {
+        let path = std::env::temp_dir().join("libtor-test-write-torrc.torrc");
+
+        let mut tor = Tor::new();
+        tor.flag(TorFlag::SocksPort(9050));
+        tor.write_torrc(&path).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, tor.to_torrc());
+
+        let loaded = Tor::from_torrc(&path);
+        assert_eq!(loaded.flags.len(), 1);
+        let expected_path = path.to_string_lossy().into_owned();
+        assert!(matches!(&loaded.flags[0], TorFlag::ConfigFile(p) if *p == expected_path));
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
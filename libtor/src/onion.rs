@@ -0,0 +1,158 @@
+//! v3 onion-service identities, computed ahead of time instead of read back off disk after boot
+//!
+//! Tor only tells you a hidden service's `.onion` address by writing a `hostname` file into its
+//! `HiddenServiceDir` once it has bootstrapped. [`OnionService`] short-circuits that: it writes
+//! the ed25519 identity key itself, in the exact format Tor expects to find there, and derives
+//! the address from the public key with the same SHA3-256/base32 construction Tor uses.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::crypto::{base32_lower, sha3_256};
+use crate::ed25519;
+
+/// 32-byte header Tor prepends to `hs_ed25519_secret_key` (format `type0`)
+const SECRET_KEY_HEADER: &[u8; 32] = b"== ed25519v1-secret: type0 ==\0\0\0";
+/// 32-byte header Tor prepends to `hs_ed25519_public_key` (format `type0`)
+const PUBLIC_KEY_HEADER: &[u8; 32] = b"== ed25519v1-public: type0 ==\0\0\0";
+
+/// `version` field baked into every v3 onion address
+const ONION_VERSION: u8 = 0x03;
+/// Domain separator mixed into the v3 onion-address checksum
+const CHECKSUM_CONST: &[u8] = b".onion checksum";
+
+/// A v3 hidden-service identity: an ed25519 keypair plus the `.onion` address it derives
+///
+/// # Example
+///
+/// ```no_run
+/// use libtor::{OnionService, Tor, TorFlag};
+///
+/// let service = OnionService::generate("/tmp/tor-rust/hs-dir")?;
+/// println!("will be reachable at {}", service.address());
+///
+/// Tor::new()
+///     .flag(TorFlag::HiddenServiceDir("/tmp/tor-rust/hs-dir".into()))
+///     .start()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct OnionService {
+    public_key: [u8; 32],
+    expanded_secret_key: [u8; 64],
+    address: String,
+}
+
+impl std::fmt::Debug for OnionService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnionService")
+            .field("public_key", &self.public_key)
+            .field("expanded_secret_key", &"[redacted]")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+impl OnionService {
+    /// Generate a fresh ed25519 identity and write it into `hidden_service_dir`
+    pub fn generate<P: AsRef<Path>>(hidden_service_dir: P) -> io::Result<OnionService> {
+        let mut seed = [0u8; 32];
+        crate::crypto::secure_random(&mut seed)?;
+
+        OnionService::from_seed(&seed, hidden_service_dir)
+    }
+
+    /// Derive an identity from a 32-byte ed25519 seed and write it into `hidden_service_dir`
+    ///
+    /// Use this to make a hidden service's address stable across runs by persisting `seed`
+    /// yourself, instead of calling [`OnionService::generate`] every time.
+    pub fn from_seed<P: AsRef<Path>>(seed: &[u8; 32], hidden_service_dir: P) -> io::Result<OnionService> {
+        let (public_key, expanded_secret_key) = ed25519::expand_seed(seed);
+        let service = OnionService {
+            public_key,
+            expanded_secret_key,
+            address: onion_address(&public_key),
+        };
+
+        service.write(hidden_service_dir)?;
+        Ok(service)
+    }
+
+    fn write<P: AsRef<Path>>(&self, hidden_service_dir: P) -> io::Result<()> {
+        let dir = hidden_service_dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let mut secret_key_file = SECRET_KEY_HEADER.to_vec();
+        secret_key_file.extend_from_slice(&self.expanded_secret_key);
+        write_private_file(&dir.join("hs_ed25519_secret_key"), &secret_key_file)?;
+
+        let mut public_key_file = PUBLIC_KEY_HEADER.to_vec();
+        public_key_file.extend_from_slice(&self.public_key);
+        fs::write(dir.join("hs_ed25519_public_key"), &public_key_file)?;
+
+        Ok(())
+    }
+
+    /// The resulting `<56 chars>.onion` address, available before Tor is even started
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The raw 32-byte ed25519 public key backing [`OnionService::address`]
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public_key
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents)
+}
+
+#[cfg(not(target_family = "unix"))]
+fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// `base32_lower(pubkey || checksum || version)`, the v3 onion-address construction
+fn onion_address(public_key: &[u8; 32]) -> String {
+    let mut checksum_input = Vec::with_capacity(CHECKSUM_CONST.len() + 32 + 1);
+    checksum_input.extend_from_slice(CHECKSUM_CONST);
+    checksum_input.extend_from_slice(public_key);
+    checksum_input.push(ONION_VERSION);
+    let checksum = &sha3_256(&checksum_input)[..2];
+
+    let mut address_bytes = Vec::with_capacity(32 + 2 + 1);
+    address_bytes.extend_from_slice(public_key);
+    address_bytes.extend_from_slice(checksum);
+    address_bytes.push(ONION_VERSION);
+
+    format!("{}.onion", base32_lower(&address_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_onion_address_all_zero_pubkey() {
+        // Independently recomputed (SHA3-256 checksum + base32) from the all-zero public key, so
+        // a one-byte error anywhere in the construction fails this rather than only a length check
+        let address = onion_address(&[0u8; 32]);
+        assert_eq!(
+            address,
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaam2dqd.onion"
+        );
+        assert_eq!(address.len(), 56 + ".onion".len());
+    }
+}
@@ -0,0 +1,59 @@
+//! Pluggable-transport configuration
+//!
+//! Lets censored-network clients bootstrap through a bridge by wrapping their connection in an
+//! obfuscation layer (obfs4, snowflake, meek, ...) instead of dialing it in the clear. Tor itself
+//! only knows how to spawn the transport binary and talk to it over the pluggable-transport IPC
+//! protocol; [`PluggableTransport`] renders the `ClientTransportPlugin`/`ServerTransportPlugin`
+//! line that tells it which binary handles which transport names.
+
+/// A `ClientTransportPlugin`/`ServerTransportPlugin` line: one or more transport names served by
+/// a single managed-transport binary
+///
+/// # Example
+///
+/// ```
+/// use libtor::{PluggableTransport, TorFlag};
+///
+/// TorFlag::ClientTransportPlugin(
+///     PluggableTransport::exec("/usr/bin/obfs4proxy")
+///         .transport("obfs4")
+///         .transport("meek_lite"),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct PluggableTransport {
+    transports: Vec<String>,
+    path: String,
+    args: Vec<String>,
+}
+
+impl PluggableTransport {
+    /// A transport binary invoked directly, i.e. an `exec <path>` proxy line
+    pub fn exec<S: Into<String>>(path: S) -> PluggableTransport {
+        PluggableTransport {
+            transports: Vec::new(),
+            path: path.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Register a transport name (e.g. `obfs4`, `snowflake`, `meek_lite`) served by this binary
+    pub fn transport<S: Into<String>>(mut self, name: S) -> PluggableTransport {
+        self.transports.push(name.into());
+        self
+    }
+
+    /// Append an extra argument passed to the transport binary on launch
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> PluggableTransport {
+        self.args.push(arg.into());
+        self
+    }
+}
+
+impl std::fmt::Display for PluggableTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![self.transports.join(","), "exec".to_string(), self.path.clone()];
+        parts.extend(self.args.iter().cloned());
+        write!(f, "{}", parts.join(" "))
+    }
+}